@@ -0,0 +1,71 @@
+use crate::price::VAT;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Published to `pricing.changed.<sku>` on every successful set_price call,
+// whether or not it ends up moving the currently-effective price — so
+// replaying subscribers see the same history the Postgres sink mirrors
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriceChangedEvent {
+  pub sku: u32,
+  pub net_retail_price: f32,
+  pub gross_retail_price: f32,
+  pub vat: VAT,
+  pub created_by: String,
+  pub effective_at: DateTime<Utc>,
+}
+
+// Publishes price-change events to a durable JetStream stream so that
+// late-joining subscribers (UPL, billing, search indexers) can replay
+// missed updates from a cursor instead of scanning the whole VecPack
+#[derive(Clone)]
+pub struct EventPublisher {
+  jetstream: async_nats::jetstream::Context,
+}
+
+const STREAM_NAME: &str = "PRICING_CHANGED";
+const SUBJECT_PREFIX: &str = "pricing.changed";
+
+impl EventPublisher {
+  // Connect to NATS and make sure the durable stream exists
+  pub async fn connect(addr: &str) -> Result<Self, async_nats::Error> {
+    let client = async_nats::connect(addr).await?;
+    let jetstream = async_nats::jetstream::new(client);
+    jetstream
+      .get_or_create_stream(async_nats::jetstream::stream::Config {
+        name: STREAM_NAME.into(),
+        subjects: vec![format!("{}.*", SUBJECT_PREFIX)],
+        ..Default::default()
+      })
+      .await?;
+    Ok(Self { jetstream })
+  }
+
+  // Publishing is best-effort and must never fail the gRPC call that triggered it;
+  // errors are logged and swallowed. The hand-off to the client only means the
+  // broker accepted the write — the stream ack is what confirms JetStream actually
+  // persisted it, so that must be awaited too or replay loses silently.
+  pub async fn publish(&self, event: &PriceChangedEvent) {
+    let subject = format!("{}.{}", SUBJECT_PREFIX, event.sku);
+    let payload = match serde_json::to_vec(event) {
+      Ok(payload) => payload,
+      Err(e) => {
+        eprintln!("Could not serialize price change event for sku {}: {}", event.sku, e);
+        return;
+      }
+    };
+    match self.jetstream.publish(subject, payload.into()).await {
+      Ok(ack) => {
+        if let Err(e) = ack.await {
+          eprintln!(
+            "JetStream did not acknowledge price change event for sku {}: {}",
+            event.sku, e
+          );
+        }
+      }
+      Err(e) => {
+        eprintln!("Could not publish price change event for sku {}: {}", event.sku, e);
+      }
+    }
+  }
+}
@@ -11,39 +11,80 @@ use tonic::{
   Request, Response, Status,
 };
 
+mod events;
 mod prelude;
 mod price;
+mod storage;
 
+use events::{EventPublisher, PriceChangedEvent};
 use prelude::*;
+use storage::PgSink;
+
+// Reject get_price_candles requests that would bucket into more candles than
+// any real dashboard renders, so an absurd date range/interval combination
+// can't be used to force unbounded work on the service
+const MAX_PRICE_CANDLES: i64 = 1000;
 
 struct PricingService {
   skus: Mutex<VecPack<price::Sku>>,
   client_upl: Mutex<UplClient<Channel>>,
+  events: Option<EventPublisher>,
+  pg: Option<PgSink>,
 }
 
 impl PricingService {
   // Init PricingService with the given DB
-  fn init(db: VecPack<price::Sku>, upl_client: UplClient<Channel>) -> Self {
+  fn init(
+    db: VecPack<price::Sku>,
+    upl_client: UplClient<Channel>,
+    events: Option<EventPublisher>,
+    pg: Option<PgSink>,
+  ) -> Self {
     Self {
       skus: Mutex::new(db),
       client_upl: Mutex::new(upl_client),
+      events,
+      pg,
     }
   }
   // Set price
   async fn set_price(&self, p: SetPriceRequest) -> ServiceResult<PriceObject> {
+    // An empty effective_at means "effective now"; otherwise it may backdate the entry.
+    // Resolved once here so every consumer below (history, postgres, events) agrees on it.
+    let effective_at = match p.effective_at.is_empty() {
+      true => Utc::now(),
+      false => DateTime::parse_from_rfc3339(&p.effective_at)
+        .map_err(|_| ServiceError::bad_request("A megadott hatálybalépési dátum hibás"))?
+        .with_timezone(&Utc),
+    };
+
+    let created_by = p.created_by.clone();
+    let vat = price::VAT::from_str(&p.vat).map_err(|e| ServiceError::bad_request(&e))?;
     let mut first_time_sku: Option<Sku> = None;
+    // Whether this write actually moved the currently-effective price
+    let mut price_changed = true;
     // If the sku has already a price set
     let sku = match self.skus.lock().await.find_id_mut(&p.sku) {
       Ok(sku_object) => {
-        match sku_object.as_mut().unpack().set_price(
+        let sku_object = sku_object.as_mut().unpack();
+        let before = (
+          sku_object.net_retail_price,
+          sku_object.gross_retail_price,
+          sku_object.vat.clone(),
+        );
+        let res = match sku_object.set_price(
           p.price_net_retail,
-          price::VAT::from_str(&p.vat).map_err(|e| ServiceError::bad_request(&e))?,
+          vat.clone(),
           p.price_gross_retail,
-          p.created_by,
+          created_by.clone(),
+          Some(effective_at),
         ) {
           Ok(res) => res.clone(),
           Err(e) => return Err(ServiceError::bad_request(&e)),
-        }
+        };
+        price_changed =
+          before != (res.net_retail_price, res.gross_retail_price, res.vat.clone());
+        res
       }
       Err(_) => {
         // If the price is set for the first time
@@ -52,9 +93,10 @@ impl PricingService {
         new_sku
           .set_price(
             p.price_net_retail,
-            price::VAT::from_str(&p.vat).map_err(|e| ServiceError::bad_request(&e))?,
+            vat.clone(),
             p.price_gross_retail,
-            p.created_by,
+            created_by.clone(),
+            Some(effective_at),
           )
           .map_err(|e| ServiceError::bad_request(&e))?;
         first_time_sku = Some(new_sku.clone());
@@ -69,19 +111,51 @@ impl PricingService {
       None => (), // Do nothing
     }
 
-    // Store prices to related UPLs
-    self
-      .client_upl
-      .lock()
-      .await
-      .set_sku_price(SetSkuPriceRequest {
+    // Mirror every history entry to postgres for analytics, regardless of whether
+    // it ended up being the currently-effective price
+    if let Some(pg) = self.pg.as_ref() {
+      let entry = price::PriceHistoryObject {
+        net_retail_price: p.price_net_retail,
+        vat: vat.clone(),
+        gross_retail_price: p.price_gross_retail,
+        created_by: created_by.clone(),
+        created_at: effective_at,
+        written_at: Utc::now(),
+      };
+      pg.append(sku.sku, &entry).await;
+    }
+
+    // Publish every successful set_price, not just ones that move the live price,
+    // so late-joining subscribers can replay the full history from a cursor.
+    // Best-effort: must not slow down or fail the gRPC response.
+    if let Some(publisher) = self.events.as_ref() {
+      let event = PriceChangedEvent {
         sku: sku.sku,
-        net_price: sku.net_retail_price,
-        vat: sku.vat.to_string(),
-        gross_price: sku.gross_retail_price,
-      })
-      .await
-      .map_err(|e| ServiceError::bad_request(&e.to_string()))?;
+        net_retail_price: p.price_net_retail,
+        gross_retail_price: p.price_gross_retail,
+        vat,
+        created_by: created_by.clone(),
+        effective_at,
+      };
+      let publisher = publisher.clone();
+      tokio::spawn(async move { publisher.publish(&event).await });
+    }
+
+    // Only push the UPL update when the change actually affects the currently-effective price
+    if price_changed {
+      self
+        .client_upl
+        .lock()
+        .await
+        .set_sku_price(SetSkuPriceRequest {
+          sku: sku.sku,
+          net_price: sku.net_retail_price,
+          vat: sku.vat.to_string(),
+          gross_price: sku.gross_retail_price,
+        })
+        .await
+        .map_err(|e| ServiceError::bad_request(&e.to_string()))?;
+    }
 
     // Return new sku as PriceObject
     Ok(sku.into())
@@ -113,7 +187,9 @@ impl PricingService {
     let till = DateTime::parse_from_rfc3339(&r.date_till)
       .map_err(|_| ServiceError::bad_request("A megadott -ig- dátum hibás"))?
       .with_timezone(&Utc);
-    // Get results
+    // Get results. Filtered on when a write actually happened (written_at), not on
+    // the chronological (effective_at) ordering of history, which a backdated or
+    // future-dated entry can leave pointing anywhere in the window
     let res = self
       .skus
       .lock()
@@ -121,15 +197,63 @@ impl PricingService {
       .iter()
       .filter(|s| {
         let sku = s.unpack();
-        if let Some(price) = sku.history.last() {
-          return price.created_at >= from && price.created_at <= till;
+        match sku.last_written_at() {
+          Some(written_at) => written_at >= from && written_at <= till,
+          None => false,
         }
-        false
       })
       .map(|s| s.unpack().sku)
       .collect::<Vec<u32>>();
     Ok(res)
   }
+  // Get the price that was effective at an arbitrary past instant.
+  // In `first_after` mode, get the earliest price effective at or after it instead.
+  async fn get_price_at(&self, r: GetPriceAtRequest) -> ServiceResult<PriceObject> {
+    let t = DateTime::parse_from_rfc3339(&r.timestamp)
+      .map_err(|_| ServiceError::bad_request("A megadott időpont hibás"))?
+      .with_timezone(&Utc);
+
+    let sku = self.skus.lock().await.find_id(&r.sku)?.unpack().clone();
+
+    let phi = sku
+      .price_at(t, r.first_after)
+      .ok_or_else(|| ServiceError::not_found("Nincs érvényes ár a megadott időpontban"))?;
+
+    Ok(PriceObject {
+      sku: sku.sku,
+      net_retail_price: phi.net_retail_price,
+      vat: phi.vat.to_string(),
+      gross_retail_price: phi.gross_retail_price,
+    })
+  }
+  // Bucket a Sku's price history into OHLC-style candles for dashboards
+  async fn get_price_candles(&self, r: GetPriceCandlesRequest) -> ServiceResult<Vec<PriceCandle>> {
+    let date_from = DateTime::parse_from_rfc3339(&r.date_from)
+      .map_err(|_| ServiceError::bad_request("A megadott -tól- dátum hibás"))?
+      .with_timezone(&Utc);
+    let date_till = DateTime::parse_from_rfc3339(&r.date_till)
+      .map_err(|_| ServiceError::bad_request("A megadott -ig- dátum hibás"))?
+      .with_timezone(&Utc);
+    let interval = price::CandleInterval::from_str(&r.interval)
+      .map_err(|e| ServiceError::bad_request(&e))?;
+    if interval.bucket_count(date_from, date_till) > MAX_PRICE_CANDLES {
+      return Err(ServiceError::bad_request(
+        "A megadott időintervallum túl sok gyertyát eredményezne",
+      ));
+    }
+
+    let res = self
+      .skus
+      .lock()
+      .await
+      .find_id(&r.sku)?
+      .unpack()
+      .candles(date_from, date_till, interval)
+      .into_iter()
+      .map(|c| c.into())
+      .collect::<Vec<PriceCandle>>();
+    Ok(res)
+  }
   // Get price history items
   async fn get_price_history(&self, r: GetPriceRequest) -> ServiceResult<Vec<PriceHistoryObject>> {
     let res = self
@@ -209,6 +333,22 @@ impl Pricing for PricingService {
     let res = self.get_latest_price_changes(request.into_inner()).await?;
     Ok(Response::new(PriceIds { price_ids: res }))
   }
+
+  async fn get_price_at(
+    &self,
+    request: Request<GetPriceAtRequest>,
+  ) -> Result<Response<PriceObject>, Status> {
+    let res = self.get_price_at(request.into_inner()).await?;
+    Ok(Response::new(res))
+  }
+
+  async fn get_price_candles(
+    &self,
+    request: Request<GetPriceCandlesRequest>,
+  ) -> Result<Response<PriceCandles>, Status> {
+    let res = self.get_price_candles(request.into_inner()).await?;
+    Ok(Response::new(PriceCandles { candles: res }))
+  }
 }
 
 #[tokio::main]
@@ -220,7 +360,34 @@ async fn main() -> prelude::ServiceResult<()> {
     .await
     .expect("Could not connect to image processer service");
 
-  let pricing_service = PricingService::init(db, client_upl);
+  // NATS publishing is optional; the service stays fully functional without it
+  let events = match env::var("SERVICE_ADDR_NATS") {
+    Ok(addr) => match EventPublisher::connect(&addr).await {
+      Ok(publisher) => Some(publisher),
+      Err(e) => {
+        eprintln!("Could not connect to NATS at {}: {}", addr, e);
+        None
+      }
+    },
+    Err(_) => None,
+  };
+
+  // Postgres mirroring is optional; the service stays fully functional without it
+  let pg = match env::var("DATABASE_URL") {
+    Ok(database_url) => match PgSink::connect(&database_url).await {
+      Ok(pg) => {
+        pg.backfill(&db).await;
+        Some(pg)
+      }
+      Err(e) => {
+        eprintln!("Could not connect to postgres: {}", e);
+        None
+      }
+    },
+    Err(_) => None,
+  };
+
+  let pricing_service = PricingService::init(db, client_upl, events, pg);
 
   let addr = env::var("SERVICE_ADDR_PRICING")
     .unwrap_or("[::1]:50061".into())
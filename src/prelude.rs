@@ -0,0 +1,49 @@
+use std::env;
+pub use std::str::FromStr;
+use tonic::Status;
+
+pub type ServiceResult<T> = Result<T, ServiceError>;
+
+#[derive(Debug)]
+pub struct ServiceError {
+  pub code: tonic::Code,
+  pub message: String,
+}
+
+impl ServiceError {
+  pub fn bad_request(msg: &str) -> Self {
+    Self {
+      code: tonic::Code::InvalidArgument,
+      message: msg.to_string(),
+    }
+  }
+  pub fn not_found(msg: &str) -> Self {
+    Self {
+      code: tonic::Code::NotFound,
+      message: msg.to_string(),
+    }
+  }
+  pub fn internal_error(msg: &str) -> Self {
+    Self {
+      code: tonic::Code::Internal,
+      message: msg.to_string(),
+    }
+  }
+}
+
+impl From<ServiceError> for Status {
+  fn from(e: ServiceError) -> Self {
+    Status::new(e.code, e.message)
+  }
+}
+
+impl From<packman::PackError> for ServiceError {
+  fn from(e: packman::PackError) -> Self {
+    ServiceError::not_found(&e.to_string())
+  }
+}
+
+// Resolve a service address from its env var, panicking if unset
+pub fn service_address(key: &str) -> String {
+  env::var(key).unwrap_or_else(|_| panic!("ENV KEY NOT FOUND: {}", key))
+}
@@ -0,0 +1,507 @@
+use chrono::{DateTime, Duration, Utc};
+use gzlib::proto::pricing::{
+  PriceCandle as PriceCandleProto, PriceHistoryObject as PriceHistoryObjectProto, PriceObject,
+};
+use packman::VecPackMember;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// Hungarian VAT categories
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum VAT {
+  AAM,
+  FAD,
+  TAM,
+  _5,
+  _18,
+  _27,
+}
+
+impl FromStr for VAT {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    use VAT::*;
+    match s {
+      "AAM" => Ok(AAM),
+      "FAD" => Ok(FAD),
+      "TAM" => Ok(TAM),
+      "5" => Ok(_5),
+      "18" => Ok(_18),
+      "27" => Ok(_27),
+      _ => Err(format!("Ismeretlen áfakulcs: {}", s)),
+    }
+  }
+}
+
+impl ToString for VAT {
+  fn to_string(&self) -> String {
+    use VAT::*;
+    match self {
+      AAM => "AAM".to_string(),
+      FAD => "FAD".to_string(),
+      TAM => "TAM".to_string(),
+      _5 => "5".to_string(),
+      _18 => "18".to_string(),
+      _27 => "27".to_string(),
+    }
+  }
+}
+
+// The bucket width for get_price_candles
+#[derive(Debug, Clone, Copy)]
+pub enum CandleInterval {
+  Daily,
+  Weekly,
+  Monthly,
+}
+
+impl FromStr for CandleInterval {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "daily" => Ok(Self::Daily),
+      "weekly" => Ok(Self::Weekly),
+      "monthly" => Ok(Self::Monthly),
+      _ => Err(format!("Ismeretlen intervallum: {}", s)),
+    }
+  }
+}
+
+impl CandleInterval {
+  fn duration(&self) -> Duration {
+    match self {
+      Self::Daily => Duration::days(1),
+      Self::Weekly => Duration::weeks(1),
+      Self::Monthly => Duration::days(30),
+    }
+  }
+
+  // How many buckets a [date_from, date_till) range would produce at this
+  // interval, so callers can reject absurd ranges before actually bucketing.
+  // Mirrors the `while bucket_start < date_till` stepping in `Sku::candles`.
+  pub fn bucket_count(&self, date_from: DateTime<Utc>, date_till: DateTime<Utc>) -> i64 {
+    let span = date_till - date_from;
+    if span <= Duration::zero() {
+      return 0;
+    }
+    let step = self.duration().num_seconds();
+    (span.num_seconds() + step - 1) / step
+  }
+}
+
+// One OHLC-style bucket over a Sku's price history. A bucket with no price
+// change in it carries forward the previous bucket's close as its own open
+#[derive(Debug, Clone)]
+pub struct PriceCandle {
+  pub bucket_start: DateTime<Utc>,
+  pub open_net_retail_price: f32,
+  pub close_net_retail_price: f32,
+  pub min_net_retail_price: f32,
+  pub max_net_retail_price: f32,
+  pub open_gross_retail_price: f32,
+  pub close_gross_retail_price: f32,
+  pub min_gross_retail_price: f32,
+  pub max_gross_retail_price: f32,
+  pub change_count: u32,
+}
+
+// A single historical price entry. `created_at` is the price's effective date
+// and can be backdated or scheduled in the future; `written_at` is the actual
+// wall-clock instant the entry was ingested and is what "latest change" means
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriceHistoryObject {
+  pub net_retail_price: f32,
+  pub vat: VAT,
+  pub gross_retail_price: f32,
+  pub created_by: String,
+  pub created_at: DateTime<Utc>,
+  pub written_at: DateTime<Utc>,
+}
+
+// A Sku with its current price and its full price history
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Sku {
+  pub sku: u32,
+  pub net_retail_price: f32,
+  pub vat: VAT,
+  pub gross_retail_price: f32,
+  pub history: Vec<PriceHistoryObject>,
+}
+
+impl Sku {
+  // Create a new Sku without any price set yet
+  pub fn new(sku: u32) -> Self {
+    Self {
+      sku,
+      net_retail_price: 0.0,
+      vat: VAT::AAM,
+      gross_retail_price: 0.0,
+      history: Vec::new(),
+    }
+  }
+  // Set a new price, inserting it into the history in chronological order so that
+  // backdated corrections arriving out of order don't clobber the live price.
+  // `effective_at` defaults to now when not given.
+  pub fn set_price(
+    &mut self,
+    net_retail_price: f32,
+    vat: VAT,
+    gross_retail_price: f32,
+    created_by: String,
+    effective_at: Option<DateTime<Utc>>,
+  ) -> Result<&Self, String> {
+    let effective_at = effective_at.unwrap_or_else(Utc::now);
+
+    let pos = self
+      .history
+      .iter()
+      .position(|h| h.created_at > effective_at)
+      .unwrap_or(self.history.len());
+    self.history.insert(
+      pos,
+      PriceHistoryObject {
+        net_retail_price,
+        vat: vat.clone(),
+        gross_retail_price,
+        created_by,
+        created_at: effective_at,
+        written_at: Utc::now(),
+      },
+    );
+
+    // The live price becomes the entry with the greatest created_at that is not in the
+    // future as of this call; a future-dated entry only goes live on a later set_price
+    let now = Utc::now();
+    if let Some(current) = self
+      .history
+      .iter()
+      .filter(|h| h.created_at <= now)
+      .max_by_key(|h| h.created_at)
+    {
+      self.net_retail_price = current.net_retail_price;
+      self.vat = current.vat.clone();
+      self.gross_retail_price = current.gross_retail_price;
+    }
+
+    Ok(self)
+  }
+  // The instant this Sku's price was last actually written, regardless of which
+  // entry it landed on in chronological (effective_at) order
+  pub fn last_written_at(&self) -> Option<DateTime<Utc>> {
+    self.history.iter().map(|h| h.written_at).max()
+  }
+  // The price effective at `t`: the entry with the greatest created_at <= t
+  // (latest-before), or, in `first_after` mode, the entry with the smallest
+  // created_at >= t (earliest-after)
+  pub fn price_at(&self, t: DateTime<Utc>, first_after: bool) -> Option<&PriceHistoryObject> {
+    if first_after {
+      self
+        .history
+        .iter()
+        .filter(|h| h.created_at >= t)
+        .min_by_key(|h| h.created_at)
+    } else {
+      self
+        .history
+        .iter()
+        .filter(|h| h.created_at <= t)
+        .max_by_key(|h| h.created_at)
+    }
+  }
+  // Bucket the history into fixed-width OHLC candles between date_from and date_till.
+  // The first bucket opens at the price effective at date_from; every later bucket
+  // with no change in it opens at the previous bucket's close.
+  pub fn candles(
+    &self,
+    date_from: DateTime<Utc>,
+    date_till: DateTime<Utc>,
+    interval: CandleInterval,
+  ) -> Vec<PriceCandle> {
+    let step = interval.duration();
+    // Strictly before date_from: an entry exactly at date_from belongs to the
+    // first bucket's own changes, not to the carried-forward baseline
+    let mut carry = self
+      .history
+      .iter()
+      .filter(|h| h.created_at < date_from)
+      .max_by_key(|h| h.created_at)
+      .cloned();
+
+    let mut candles = Vec::new();
+    let mut bucket_start = date_from;
+    while bucket_start < date_till {
+      let bucket_end = (bucket_start + step).min(date_till);
+
+      let changes: Vec<&PriceHistoryObject> = self
+        .history
+        .iter()
+        .filter(|h| h.created_at >= bucket_start && h.created_at < bucket_end)
+        .collect();
+
+      // With no carried-forward baseline, the bucket opens at its own first change
+      let open = carry.clone().or_else(|| changes.first().copied().cloned());
+      let close = changes.last().copied().cloned().or_else(|| open.clone());
+
+      if let Some(close) = close {
+        let mut min_net = close.net_retail_price;
+        let mut max_net = close.net_retail_price;
+        let mut min_gross = close.gross_retail_price;
+        let mut max_gross = close.gross_retail_price;
+        for h in open.iter().chain(changes.iter().map(|h| *h)) {
+          min_net = min_net.min(h.net_retail_price);
+          max_net = max_net.max(h.net_retail_price);
+          min_gross = min_gross.min(h.gross_retail_price);
+          max_gross = max_gross.max(h.gross_retail_price);
+        }
+        let open = open.unwrap_or_else(|| close.clone());
+
+        candles.push(PriceCandle {
+          bucket_start,
+          open_net_retail_price: open.net_retail_price,
+          close_net_retail_price: close.net_retail_price,
+          min_net_retail_price: min_net,
+          max_net_retail_price: max_net,
+          open_gross_retail_price: open.gross_retail_price,
+          close_gross_retail_price: close.gross_retail_price,
+          min_gross_retail_price: min_gross,
+          max_gross_retail_price: max_gross,
+          change_count: changes.len() as u32,
+        });
+        carry = Some(close);
+      }
+
+      bucket_start = bucket_end;
+    }
+
+    candles
+  }
+}
+
+impl VecPackMember for Sku {
+  type Out = u32;
+  fn get_id(&self) -> &Self::Out {
+    &self.sku
+  }
+}
+
+impl From<Sku> for PriceObject {
+  fn from(s: Sku) -> Self {
+    Self {
+      sku: s.sku,
+      net_retail_price: s.net_retail_price,
+      vat: s.vat.to_string(),
+      gross_retail_price: s.gross_retail_price,
+    }
+  }
+}
+
+impl From<PriceCandle> for PriceCandleProto {
+  fn from(c: PriceCandle) -> Self {
+    Self {
+      bucket_start: c.bucket_start.to_rfc3339(),
+      open_net_retail_price: c.open_net_retail_price,
+      close_net_retail_price: c.close_net_retail_price,
+      min_net_retail_price: c.min_net_retail_price,
+      max_net_retail_price: c.max_net_retail_price,
+      open_gross_retail_price: c.open_gross_retail_price,
+      close_gross_retail_price: c.close_gross_retail_price,
+      min_gross_retail_price: c.min_gross_retail_price,
+      max_gross_retail_price: c.max_gross_retail_price,
+      change_count: c.change_count,
+    }
+  }
+}
+
+impl From<PriceHistoryObject> for PriceHistoryObjectProto {
+  fn from(p: PriceHistoryObject) -> Self {
+    Self {
+      net_retail_price: p.net_retail_price,
+      vat: p.vat.to_string(),
+      gross_retail_price: p.gross_retail_price,
+      created_by: p.created_by,
+      created_at: p.created_at.to_rfc3339(),
+      written_at: p.written_at.to_rfc3339(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(rfc3339: &str) -> DateTime<Utc> {
+    rfc3339.parse().unwrap()
+  }
+
+  #[test]
+  fn set_price_keeps_history_chronological_when_backdated() {
+    let mut sku = Sku::new(1);
+    sku
+      .set_price(30.0, VAT::_27, 38.0, "a".into(), Some(at("2020-01-03T00:00:00Z")))
+      .unwrap();
+    sku
+      .set_price(10.0, VAT::_27, 13.0, "a".into(), Some(at("2020-01-01T00:00:00Z")))
+      .unwrap();
+    sku
+      .set_price(20.0, VAT::_27, 25.0, "a".into(), Some(at("2020-01-02T00:00:00Z")))
+      .unwrap();
+
+    let created_ats: Vec<DateTime<Utc>> = sku.history.iter().map(|h| h.created_at).collect();
+    assert_eq!(
+      created_ats,
+      vec![
+        at("2020-01-01T00:00:00Z"),
+        at("2020-01-02T00:00:00Z"),
+        at("2020-01-03T00:00:00Z"),
+      ]
+    );
+    // The live price must come from the latest entry, not the last one inserted
+    assert_eq!(sku.net_retail_price, 30.0);
+  }
+
+  #[test]
+  fn last_written_at_tracks_write_order_not_effective_at_order() {
+    let mut sku = Sku::new(1);
+    sku
+      .set_price(30.0, VAT::_27, 38.0, "a".into(), Some(at("2020-01-03T00:00:00Z")))
+      .unwrap();
+    let after_first_write = sku.last_written_at().unwrap();
+
+    // A backdated correction is written later in wall-clock time even though it
+    // lands earlier in the chronological (effective_at) history
+    sku
+      .set_price(10.0, VAT::_27, 13.0, "a".into(), Some(at("2020-01-01T00:00:00Z")))
+      .unwrap();
+
+    assert!(sku.history[0].created_at < sku.history[1].created_at);
+    assert!(sku.last_written_at().unwrap() >= after_first_write);
+  }
+
+  #[test]
+  fn set_price_backdated_correction_does_not_move_live_price() {
+    let mut sku = Sku::new(1);
+    sku
+      .set_price(50.0, VAT::_27, 63.0, "a".into(), Some(at("2020-01-05T00:00:00Z")))
+      .unwrap();
+    sku
+      .set_price(5.0, VAT::_27, 6.0, "a".into(), Some(at("2020-01-01T00:00:00Z")))
+      .unwrap();
+
+    assert_eq!(sku.net_retail_price, 50.0);
+    assert_eq!(sku.history.len(), 2);
+  }
+
+  #[test]
+  fn price_at_returns_latest_before_by_default() {
+    let mut sku = Sku::new(1);
+    sku
+      .set_price(10.0, VAT::_27, 13.0, "a".into(), Some(at("2020-01-01T00:00:00Z")))
+      .unwrap();
+    sku
+      .set_price(20.0, VAT::_27, 25.0, "a".into(), Some(at("2020-01-10T00:00:00Z")))
+      .unwrap();
+
+    let phi = sku.price_at(at("2020-01-05T00:00:00Z"), false).unwrap();
+    assert_eq!(phi.net_retail_price, 10.0);
+  }
+
+  #[test]
+  fn price_at_first_after_returns_earliest_at_or_after() {
+    let mut sku = Sku::new(1);
+    sku
+      .set_price(10.0, VAT::_27, 13.0, "a".into(), Some(at("2020-01-01T00:00:00Z")))
+      .unwrap();
+    sku
+      .set_price(20.0, VAT::_27, 25.0, "a".into(), Some(at("2020-01-10T00:00:00Z")))
+      .unwrap();
+
+    let phi = sku.price_at(at("2020-01-05T00:00:00Z"), true).unwrap();
+    assert_eq!(phi.net_retail_price, 20.0);
+  }
+
+  #[test]
+  fn price_at_not_found_before_first_history_item() {
+    let mut sku = Sku::new(1);
+    sku
+      .set_price(10.0, VAT::_27, 13.0, "a".into(), Some(at("2020-01-10T00:00:00Z")))
+      .unwrap();
+
+    assert!(sku.price_at(at("2020-01-01T00:00:00Z"), false).is_none());
+  }
+
+  #[test]
+  fn candles_first_bucket_opens_at_its_own_first_change_without_carry() {
+    let mut sku = Sku::new(1);
+    // Both changes land inside the very first bucket and nothing predates date_from
+    sku
+      .set_price(10.0, VAT::_27, 13.0, "a".into(), Some(at("2020-01-01T00:00:00Z")))
+      .unwrap();
+    sku
+      .set_price(20.0, VAT::_27, 25.0, "a".into(), Some(at("2020-01-01T06:00:00Z")))
+      .unwrap();
+
+    let candles = sku.candles(
+      at("2020-01-01T00:00:00Z"),
+      at("2020-01-02T00:00:00Z"),
+      CandleInterval::Daily,
+    );
+
+    assert_eq!(candles.len(), 1);
+    assert_eq!(candles[0].open_net_retail_price, 10.0);
+    assert_eq!(candles[0].close_net_retail_price, 20.0);
+    assert_eq!(candles[0].change_count, 2);
+  }
+
+  #[test]
+  fn candles_do_not_double_count_an_entry_exactly_at_date_from() {
+    let mut sku = Sku::new(1);
+    sku
+      .set_price(10.0, VAT::_27, 13.0, "a".into(), Some(at("2020-01-01T00:00:00Z")))
+      .unwrap();
+
+    let candles = sku.candles(
+      at("2020-01-01T00:00:00Z"),
+      at("2020-01-02T00:00:00Z"),
+      CandleInterval::Daily,
+    );
+
+    assert_eq!(candles.len(), 1);
+    assert_eq!(candles[0].change_count, 1);
+  }
+
+  #[test]
+  fn candles_carry_forward_previous_close_when_bucket_has_no_change() {
+    let mut sku = Sku::new(1);
+    sku
+      .set_price(10.0, VAT::_27, 13.0, "a".into(), Some(at("2020-01-01T00:00:00Z")))
+      .unwrap();
+
+    let candles = sku.candles(
+      at("2020-01-01T00:00:00Z"),
+      at("2020-01-03T00:00:00Z"),
+      CandleInterval::Daily,
+    );
+
+    assert_eq!(candles.len(), 2);
+    assert_eq!(candles[1].open_net_retail_price, candles[0].close_net_retail_price);
+    assert_eq!(candles[1].change_count, 0);
+  }
+
+  #[test]
+  fn bucket_count_matches_the_number_of_candles_produced() {
+    let count = CandleInterval::Daily.bucket_count(at("2020-01-01T00:00:00Z"), at("2020-01-03T00:00:00Z"));
+    assert_eq!(count, 2);
+  }
+
+  #[test]
+  fn bucket_count_is_zero_for_an_empty_or_inverted_range() {
+    assert_eq!(
+      CandleInterval::Daily.bucket_count(at("2020-01-02T00:00:00Z"), at("2020-01-01T00:00:00Z")),
+      0
+    );
+    assert_eq!(
+      CandleInterval::Daily.bucket_count(at("2020-01-01T00:00:00Z"), at("2020-01-01T00:00:00Z")),
+      0
+    );
+  }
+}
@@ -0,0 +1,106 @@
+use crate::price::{PriceHistoryObject, Sku};
+use packman::VecPack;
+use sqlx::postgres::PgPoolOptions;
+
+// Mirrors price history into Postgres for analytical/BI queries. Entirely
+// optional — the service is fully functional without DATABASE_URL set.
+// SSL is controlled by the connection string (e.g. `?sslmode=require`), not
+// forced on, so local/dev setups without TLS keep working.
+pub struct PgSink {
+  pool: sqlx::PgPool,
+}
+
+const UPSERT_SQL: &str = "
+  INSERT INTO price_history (sku, net_retail_price, gross_retail_price, vat, created_by, created_at)
+  VALUES ($1, $2, $3, $4, $5, $6)
+  ON CONFLICT (sku, created_at) DO UPDATE SET
+    net_retail_price = EXCLUDED.net_retail_price,
+    gross_retail_price = EXCLUDED.gross_retail_price,
+    vat = EXCLUDED.vat,
+    created_by = EXCLUDED.created_by";
+
+// Commit this many rows per backfill transaction, so a failure partway
+// through a large backfill only loses that one chunk, not the whole history
+const BACKFILL_CHUNK_SIZE: usize = 500;
+
+impl PgSink {
+  // Connect and make sure the price_history table exists
+  pub async fn connect(database_url: &str) -> Result<Self, sqlx::Error> {
+    let pool = PgPoolOptions::new()
+      .max_connections(5)
+      .connect(database_url)
+      .await?;
+    sqlx::query(
+      "CREATE TABLE IF NOT EXISTS price_history (
+        sku BIGINT NOT NULL,
+        net_retail_price REAL NOT NULL,
+        gross_retail_price REAL NOT NULL,
+        vat TEXT NOT NULL,
+        created_by TEXT NOT NULL,
+        created_at TIMESTAMPTZ NOT NULL,
+        PRIMARY KEY (sku, created_at)
+      )",
+    )
+    .execute(&pool)
+    .await?;
+    Ok(Self { pool })
+  }
+
+  // Fast append path for a single live price change. Best-effort: failures are
+  // logged rather than propagated, so postgres hiccups never fail set_price.
+  pub async fn append(&self, sku: u32, entry: &PriceHistoryObject) {
+    let res = sqlx::query(UPSERT_SQL)
+      .bind(sku as i64)
+      .bind(entry.net_retail_price)
+      .bind(entry.gross_retail_price)
+      .bind(entry.vat.to_string())
+      .bind(&entry.created_by)
+      .bind(entry.created_at)
+      .execute(&self.pool)
+      .await;
+    if let Err(e) = res {
+      eprintln!(
+        "Could not mirror price history for sku {} to postgres: {}",
+        sku, e
+      );
+    }
+  }
+
+  // One-shot startup walk of the whole VecPack, upserting every history row.
+  // Every history row is cloned into one list first; chunking then bounds each
+  // backfill transaction, so a failed chunk is rolled back and logged while the
+  // other chunks are still written. A failed chunk is not retried.
+  pub async fn backfill(&self, db: &VecPack<Sku>) {
+    let rows: Vec<(u32, PriceHistoryObject)> = db
+      .iter()
+      .flat_map(|s| {
+        let sku = s.unpack();
+        sku.history.iter().map(move |h| (sku.sku, h.clone()))
+      })
+      .collect();
+
+    println!("Backfilling {} price history rows into postgres...", rows.len());
+    for chunk in rows.chunks(BACKFILL_CHUNK_SIZE) {
+      if let Err(e) = self.upsert_chunk(chunk).await {
+        eprintln!("Could not backfill a chunk of price history to postgres: {}", e);
+      }
+    }
+    println!("Postgres backfill done");
+  }
+
+  async fn upsert_chunk(&self, chunk: &[(u32, PriceHistoryObject)]) -> Result<(), sqlx::Error> {
+    let mut tx = self.pool.begin().await?;
+    for (sku, entry) in chunk {
+      sqlx::query(UPSERT_SQL)
+        .bind(*sku as i64)
+        .bind(entry.net_retail_price)
+        .bind(entry.gross_retail_price)
+        .bind(entry.vat.to_string())
+        .bind(&entry.created_by)
+        .bind(entry.created_at)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+  }
+}